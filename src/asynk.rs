@@ -0,0 +1,415 @@
+use super::*;
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use reqwest::{redirect::Policy, Client, Identity};
+use tokio::task::spawn_blocking;
+
+const DEFAULT_CONCURRENCY: usize = 16;
+
+/// Async counterpart of [`BlobStore`](crate::BlobStore): the same key/value
+/// contract, but backed by futures instead of blocking calls so it can be
+/// driven from a `buffer_unordered` stream without tying up worker threads.
+#[async_trait]
+pub trait AsyncBlobStore {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), CfKvFsError>;
+    async fn get(&self, key: &str) -> Result<Vec<u8>, CfKvFsError>;
+    async fn delete(&self, key: &str) -> Result<(), CfKvFsError>;
+}
+
+/// Default async backend, talking to the same Cloudflare-KV-style endpoint
+/// as [`HttpKvStore`](crate::store::HttpKvStore) via `reqwest::Client`.
+pub struct HttpKvStoreAsync {
+    client: Client,
+    endpoint: String,
+    prefix: String,
+}
+
+impl HttpKvStoreAsync {
+    pub fn new(
+        endpoint: String,
+        prefix: String,
+        header: Option<HeaderMap>,
+        pem: Option<Vec<u8>>,
+    ) -> Option<Self> {
+        let mut builder = Client::builder()
+            .redirect(Policy::none())
+            .no_proxy()
+            .http2_prior_knowledge();
+        if let Some(header) = header {
+            builder = builder.default_headers(header);
+        }
+        if let Some(pem) = pem {
+            if let Ok(identity) = Identity::from_pem(&pem) {
+                builder = builder.identity(identity);
+            }
+        }
+        builder
+            .build()
+            .ok()
+            .map(|client| Self { client, endpoint, prefix })
+    }
+}
+
+#[async_trait]
+impl AsyncBlobStore for HttpKvStoreAsync {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), CfKvFsError> {
+        self.client
+            .post(format!("{}/{}/{}", self.endpoint, self.prefix, key))
+            .body(data)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(CfKvFsError::ReqwestError)
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, CfKvFsError> {
+        let resp = self
+            .client
+            .get(format!("{}/{}/{}", self.endpoint, self.prefix, key))
+            .send()
+            .await
+            .map_err(CfKvFsError::ReqwestError)?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(CfKvFsError::NotFound);
+        }
+        Ok(resp
+            .bytes()
+            .await
+            .map_err(CfKvFsError::ReqwestError)?
+            .to_vec())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), CfKvFsError> {
+        let resp = self
+            .client
+            .delete(format!("{}/{}/{}", self.endpoint, self.prefix, key))
+            .send()
+            .await
+            .map_err(CfKvFsError::ReqwestError)?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(CfKvFsError::NotFound);
+        }
+        Ok(())
+    }
+}
+
+/// Adapts any blocking [`BlobStore`](crate::store::BlobStore) (e.g. the
+/// filesystem or in-memory backends) onto [`AsyncBlobStore`] by running each
+/// call on the blocking thread pool, so `build_async` works with the same
+/// backends `build` does.
+pub struct BlockingStoreAdapter(Arc<dyn BlobStore + Send + Sync>);
+
+impl BlockingStoreAdapter {
+    pub fn new(store: Arc<dyn BlobStore + Send + Sync>) -> Self {
+        Self(store)
+    }
+}
+
+#[async_trait]
+impl AsyncBlobStore for BlockingStoreAdapter {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), CfKvFsError> {
+        let store = self.0.clone();
+        let key = key.to_string();
+        spawn_blocking(move || store.put(&key, data))
+            .await
+            .expect("blocking store task panicked")
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, CfKvFsError> {
+        let store = self.0.clone();
+        let key = key.to_string();
+        spawn_blocking(move || store.get(&key))
+            .await
+            .expect("blocking store task panicked")
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), CfKvFsError> {
+        let store = self.0.clone();
+        let key = key.to_string();
+        spawn_blocking(move || store.delete(&key))
+            .await
+            .expect("blocking store task panicked")
+    }
+}
+
+async fn cache_get(key: String) -> Option<Vec<u8>> {
+    spawn_blocking(move || CfKvFs::get_kv_cache().lock().unwrap().get(key).ok().flatten())
+        .await
+        .expect("cache task panicked")
+}
+
+async fn cache_put(key: String, value: Vec<u8>) -> Result<Vec<u8>, CfKvFsError> {
+    spawn_blocking(move || CfKvFs::get_kv_cache().lock().unwrap().put(key, value))
+        .await
+        .expect("cache task panicked")
+}
+
+/// Async sibling of [`CfKvFs`]: same chunking, encryption and retry
+/// behaviour, but chunk uploads/downloads run concurrently through a
+/// `buffer_unordered(concurrency)` stream instead of blocking worker threads.
+pub struct CfKvFsAsync {
+    pub(crate) store: Box<dyn AsyncBlobStore + Send + Sync>,
+    pub(crate) reducer: Option<Box<dyn Fn(Vec<u8>) -> Vec<u8> + Sync>>,
+    pub(crate) chunk_min: usize,
+    pub(crate) chunk_avg: usize,
+    pub(crate) chunk_max: usize,
+    pub(crate) encryption_key: Option<[u8; 32]>,
+    pub(crate) concurrency: usize,
+    pub(crate) legacy_index: bool,
+}
+
+impl CfKvFsAsync {
+    pub(crate) fn default_concurrency() -> usize {
+        DEFAULT_CONCURRENCY
+    }
+
+    pub(crate) async fn post_data(&self, name: &str, data: Vec<u8>, index: bool) -> Option<ChunkHash> {
+        let mut retry = 0;
+        let data = if let (Some(reducer), false) = (&self.reducer, index) {
+            reducer(data)
+        } else {
+            data
+        };
+        let data = if let Some(key) = &self.encryption_key {
+            let nonce = if index {
+                crypto::derive_nonce(&data)
+            } else {
+                crypto::random_nonce()
+            };
+            match crypto::seal(key, &nonce, &data) {
+                Ok(sealed) => sealed,
+                Err(err) => {
+                    error!("Failed to encrypt blob: {}", err);
+                    return None;
+                }
+            }
+        } else {
+            data
+        };
+        let hash = ChunkHash::compute(&data);
+        let key = format!(
+            "{}:{}",
+            name,
+            if index {
+                "index".to_string()
+            } else {
+                hash.to_string()
+            }
+        );
+        while let Err(err) = self.store.put(&key, data.clone()).await {
+            if retry > 3 {
+                error!("Failed to save blob: {}", err);
+                return None;
+            } else {
+                retry += 1;
+            }
+        }
+        Some(hash)
+    }
+
+    /// Chunks `data`, uploads every chunk, appends their hashes to the
+    /// `:chunks` log, and returns the packed `ChunkHash` index bytes without
+    /// writing them as an index — shared by `put_blob` and `put_snapshot`,
+    /// which differ only in where the index ends up. Errs
+    /// instead of returning a truncated index if any chunk failed to upload.
+    pub(crate) async fn build_hash_list(&self, name: &str, data: Vec<u8>) -> Result<Vec<u8>, CfKvFsError> {
+        let chunks = FastCdcChunker::new(&data, self.chunk_min, self.chunk_avg, self.chunk_max)
+            .map(|chunk| chunk.to_vec())
+            .collect::<Vec<_>>();
+        let mut hashes = stream::iter(chunks.into_iter().enumerate())
+            .map(|(index, chunk)| async move { (index, self.post_data(name, chunk, false).await) })
+            .buffer_unordered(self.concurrency)
+            .collect::<Vec<_>>()
+            .await;
+        hashes.sort_by_key(|(index, _)| *index);
+        let failed = hashes.iter().filter(|(_, hash)| hash.is_none()).count();
+        if failed > 0 {
+            return Err(CfKvFsError::UploadFailed(failed));
+        }
+        let hash_list = hashes
+            .into_iter()
+            .filter_map(|(_, hash)| hash)
+            .flat_map(|hash| *hash.as_bytes())
+            .collect::<Vec<_>>();
+        if let Err(err) = self.append_log(&format!("{}:chunks", name), &hash_list).await {
+            error!("Failed to record chunk log: {}", err);
+        }
+        Ok(hash_list)
+    }
+
+    /// Appends `entry` to an append-only log stored at `key`, creating it if
+    /// absent. `AsyncBlobStore` has no "list all keys" primitive, so the
+    /// `:chunks` and `:snaps` logs are how `gc`/`list_snapshots` learn what
+    /// exists.
+    pub(crate) async fn append_log(&self, key: &str, entry: &[u8]) -> Result<(), CfKvFsError> {
+        let mut log = match self.store.get(key).await {
+            Ok(existing) => existing,
+            Err(CfKvFsError::NotFound) => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        log.extend_from_slice(entry);
+        self.store.put(key, log).await
+    }
+
+    pub async fn put_blob(&self, name: &str, data: Vec<u8>) -> Result<(), CfKvFsError> {
+        let hash_list = self.build_hash_list(name, data).await?;
+        self.post_data(name, hash_list, true)
+            .await
+            .ok_or(CfKvFsError::UploadFailed(1))?;
+        Ok(())
+    }
+
+    pub(crate) async fn get_data(&self, name: &str, hash: Option<ChunkHash>) -> Result<Vec<u8>, CfKvFsError> {
+        let key = format!(
+            "{}:{}",
+            name,
+            match &hash {
+                Some(hash) => hash.to_string(),
+                None => "index".to_string(),
+            }
+        );
+        if let Some(value) = cache_get(key.clone()).await {
+            return Ok(value);
+        }
+        let mut retry = 0;
+        let buf: Vec<u8>;
+        loop {
+            match self.store.get(&key).await.and_then(|data| match &hash {
+                None => Ok(data),
+                Some(hash) if ChunkHash::compute(&data) == *hash => Ok(data),
+                Some(_) => Err(CfKvFsError::HashError),
+            }) {
+                Ok(data) => {
+                    buf = data;
+                    break;
+                }
+                Err(err) => {
+                    if retry > 3 {
+                        return Err(err);
+                    } else {
+                        retry += 1;
+                    }
+                }
+            }
+        }
+        let data = cache_put(key, buf).await?;
+        let data = if let Some(key) = &self.encryption_key {
+            crypto::open(key, &data)?
+        } else {
+            data
+        };
+        if let (Some(reducer), true) = (&self.reducer, hash.is_some()) {
+            Ok(reducer(data))
+        } else {
+            Ok(data)
+        }
+    }
+
+    /// Reads a blob whose index predates the switch to full-width
+    /// `ChunkHash` keys, i.e. packed little-endian `i64`s instead of 32-byte
+    /// digests.
+    pub(crate) async fn get_data_legacy(&self, name: &str, hash: i64) -> Result<Vec<u8>, CfKvFsError> {
+        let key = format!(
+            "{}:{}",
+            name,
+            if hash == 0 {
+                "index".into()
+            } else {
+                hash.to_string()
+            }
+        );
+        if let Some(value) = cache_get(key.clone()).await {
+            return Ok(value);
+        }
+        let mut retry = 0;
+        let buf: Vec<u8>;
+        loop {
+            match self.store.get(&key).await.and_then(|data| {
+                if hash == 0 || get_hash(&data) == hash {
+                    Ok(data)
+                } else {
+                    Err(CfKvFsError::HashError)
+                }
+            }) {
+                Ok(data) => {
+                    buf = data;
+                    break;
+                }
+                Err(err) => {
+                    if retry > 3 {
+                        return Err(err);
+                    } else {
+                        retry += 1;
+                    }
+                }
+            }
+        }
+        let data = cache_put(key, buf).await?;
+        let data = if let Some(key) = &self.encryption_key {
+            crypto::open(key, &data)?
+        } else {
+            data
+        };
+        if let (Some(reducer), false) = (&self.reducer, hash == 0) {
+            Ok(reducer(data))
+        } else {
+            Ok(data)
+        }
+    }
+
+    pub async fn get_blob(&self, name: &str) -> Result<Vec<u8>, CfKvFsError> {
+        if self.legacy_index {
+            return self.get_blob_legacy(name).await;
+        }
+        let data = self.get_data(name, None).await?;
+        self.assemble_blob(name, data).await
+    }
+
+    /// Reassembles a blob from a packed `ChunkHash` index — shared by
+    /// `get_blob` and `get_snapshot`, which differ only in where the index
+    /// comes from.
+    pub(crate) async fn assemble_blob(&self, name: &str, index: Vec<u8>) -> Result<Vec<u8>, CfKvFsError> {
+        if index.len() % 32 != 0 {
+            return Err(CfKvFsError::MalformedIndex);
+        }
+        let hashes = index
+            .chunks(32)
+            .map(ChunkHash::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut chunks = stream::iter(hashes.into_iter().enumerate())
+            .map(|(index, hash)| async move { (index, self.get_data(name, Some(hash)).await) })
+            .buffer_unordered(self.concurrency)
+            .collect::<Vec<_>>()
+            .await;
+        chunks.sort_by_key(|(index, _)| *index);
+        let mut blob = Vec::new();
+        for (_, chunk) in chunks {
+            blob.extend(chunk?);
+        }
+        Ok(blob)
+    }
+
+    async fn get_blob_legacy(&self, name: &str) -> Result<Vec<u8>, CfKvFsError> {
+        let data = self.get_data_legacy(name, 0).await?;
+        let hashes = data
+            .chunks(8)
+            .map(|hash| hash.try_into())
+            .collect::<Result<Vec<[u8; 8]>, _>>()?;
+        let mut chunks = stream::iter(hashes.into_iter().enumerate())
+            .map(|(index, hash)| async move {
+                (
+                    index,
+                    self.get_data_legacy(name, i64::from_le_bytes(hash)).await,
+                )
+            })
+            .buffer_unordered(self.concurrency)
+            .collect::<Vec<_>>()
+            .await;
+        chunks.sort_by_key(|(index, _)| *index);
+        let mut blob = Vec::new();
+        for (_, chunk) in chunks {
+            blob.extend(chunk?);
+        }
+        Ok(blob)
+    }
+}
@@ -0,0 +1,95 @@
+use super::*;
+
+/// Below this many bytes into a chunk, no cut point is considered.
+pub const MIN_SIZE: usize = 256 * 1024;
+/// Target average chunk size; the mask switches from `MASK_S` to `MASK_L` here.
+pub const AVG_SIZE: usize = 1024 * 1024;
+/// A chunk is force-cut once it reaches this many bytes.
+pub const MAX_SIZE: usize = 4 * 1024 * 1024;
+
+// Masks with a different popcount bias the boundary test: MASK_S (more set bits)
+// is harder to satisfy and used while a chunk is still small, MASK_L (fewer set
+// bits) is easier to satisfy and used once a chunk has grown past the average
+// target, so chunks converge on `AVG_SIZE` without clustering at the extremes.
+const MASK_S: u64 = 0x0003_5907_0353_0000;
+const MASK_L: u64 = 0x0000_d900_0353_0000;
+
+lazy_static! {
+    static ref GEAR: [u64; 256] = generate_gear_table();
+}
+
+fn generate_gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut seed = 0x9E3779B97F4A7C15u64;
+    for slot in table.iter_mut() {
+        seed = seed.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        *slot = z ^ (z >> 31);
+    }
+    table
+}
+
+/// Content-defined chunker following the FastCDC scheme: chunk boundaries are
+/// derived from a rolling fingerprint over the data itself, so inserting or
+/// removing bytes only reshuffles the chunk(s) touched, not everything after.
+pub struct FastCdcChunker<'a> {
+    data: &'a [u8],
+    pos: usize,
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+}
+
+impl<'a> FastCdcChunker<'a> {
+    pub fn new(data: &'a [u8], min_size: usize, avg_size: usize, max_size: usize) -> Self {
+        Self {
+            data,
+            pos: 0,
+            min_size,
+            avg_size,
+            max_size,
+        }
+    }
+
+    fn next_cut(&self) -> usize {
+        let remaining = self.data.len() - self.pos;
+        if remaining <= self.min_size {
+            return remaining;
+        }
+        let max = remaining.min(self.max_size);
+        let avg = remaining.min(self.avg_size);
+        let mut fp: u64 = 0;
+        let mut offset = self.min_size;
+        while offset < avg {
+            fp = (fp << 1).wrapping_add(GEAR[self.data[self.pos + offset] as usize]);
+            if fp & MASK_S == 0 {
+                return offset + 1;
+            }
+            offset += 1;
+        }
+        while offset < max {
+            fp = (fp << 1).wrapping_add(GEAR[self.data[self.pos + offset] as usize]);
+            if fp & MASK_L == 0 {
+                return offset + 1;
+            }
+            offset += 1;
+        }
+        max
+    }
+}
+
+impl<'a> Iterator for FastCdcChunker<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.data.len() {
+            return None;
+        }
+        let len = self.next_cut();
+        let chunk = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Some(chunk)
+    }
+}
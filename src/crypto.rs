@@ -0,0 +1,59 @@
+use super::*;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    XChaCha20Poly1305, XNonce,
+};
+use rand::RngCore;
+use sha3::{
+    digest::{ExtendableOutputDirty, Reset, Update},
+    Shake256,
+};
+use std::io::Read;
+
+pub const NONCE_SIZE: usize = 24;
+
+/// Random nonce for chunk data, where reuse across uploads is never expected.
+pub fn random_nonce() -> [u8; NONCE_SIZE] {
+    let mut nonce = [0u8; NONCE_SIZE];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    nonce
+}
+
+/// Deterministic nonce derived from the plaintext being sealed (an index or a
+/// snapshot manifest), so re-uploading identical content reproduces the same
+/// ciphertext instead of a new one each time — and, unlike a nonce derived
+/// from the key name alone, never reuses a nonce to seal two different
+/// plaintexts under the same key.
+pub fn derive_nonce(data: impl AsRef<[u8]>) -> [u8; NONCE_SIZE] {
+    let mut hasher = Shake256::default();
+    hasher.update(data.as_ref());
+    let mut buf = [0u8; NONCE_SIZE];
+    if hasher.finalize_xof_dirty().read(&mut buf).is_err() {
+        buf = [0u8; NONCE_SIZE];
+    }
+    buf
+}
+
+/// Seals `data` with XChaCha20-Poly1305, returning `nonce || ciphertext || tag`.
+pub fn seal(key: &[u8; 32], nonce: &[u8; NONCE_SIZE], data: &[u8]) -> Result<Vec<u8>, CfKvFsError> {
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let sealed = cipher
+        .encrypt(XNonce::from_slice(nonce), data)
+        .map_err(|_| CfKvFsError::EncryptError)?;
+    let mut out = Vec::with_capacity(NONCE_SIZE + sealed.len());
+    out.extend_from_slice(nonce);
+    out.extend_from_slice(&sealed);
+    Ok(out)
+}
+
+/// Reverses [`seal`], verifying the Poly1305 tag before returning the plaintext.
+pub fn open(key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>, CfKvFsError> {
+    if data.len() < NONCE_SIZE {
+        return Err(CfKvFsError::DecryptError);
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+    let cipher = XChaCha20Poly1305::new(key.into());
+    cipher
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| CfKvFsError::DecryptError)
+}
@@ -1,26 +1,28 @@
+pub mod asynk;
+mod chunker;
+mod crypto;
 mod database;
+pub mod snapshot;
+pub mod store;
 mod utils;
 
+use asynk::CfKvFsAsync;
+use chunker::FastCdcChunker;
 use database::{KvCache, LruKvCache, SqliteKvCache};
 use lazy_static::lazy_static;
 use log::error;
-use rayon::prelude::*;
-use reqwest::{
-    blocking::Client,
-    header::{HeaderMap, HeaderValue, AUTHORIZATION},
-    redirect::Policy,
-    Identity,
-};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
 use std::{
-    convert::TryInto,
+    convert::{TryFrom, TryInto},
+    io::{Read, Write},
     iter::FromIterator,
     path::{Path, PathBuf},
     sync::{Arc, Mutex, RwLock},
 };
+use store::{BlobStore, HttpKvStore};
 use thiserror::Error;
-use utils::get_hash;
-
-const CHUNK_SIZE: usize = 1024 * 1024;
+use tokio::runtime::Runtime;
+use utils::{get_hash, ChunkHash};
 
 #[derive(Debug, Error)]
 pub enum CfKvFsError {
@@ -34,6 +36,18 @@ pub enum CfKvFsError {
     IntParseConvertError(#[from] std::array::TryFromSliceError),
     #[error("Data invalid")]
     HashError,
+    #[error("Failed to encrypt data")]
+    EncryptError,
+    #[error("Failed to decrypt data")]
+    DecryptError,
+    #[error("Filesystem store error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Key not found in store")]
+    NotFound,
+    #[error("Index blob has a malformed length or hash encoding")]
+    MalformedIndex,
+    #[error("Failed to upload {0} chunk(s) to the store")]
+    UploadFailed(usize),
 }
 
 pub struct CfKvFsBuilder {
@@ -44,6 +58,13 @@ pub struct CfKvFsBuilder {
     reducer: Option<Box<dyn Fn(Vec<u8>) -> Vec<u8> + Sync>>,
     path: Option<PathBuf>,
     table: Option<String>,
+    chunk_min: usize,
+    chunk_avg: usize,
+    chunk_max: usize,
+    encryption_key: Option<[u8; 32]>,
+    store: Option<Box<dyn BlobStore + Send + Sync>>,
+    concurrency: usize,
+    legacy_index: bool,
 }
 
 impl CfKvFsBuilder {
@@ -60,6 +81,13 @@ impl CfKvFsBuilder {
             reducer: None,
             path: None,
             table: None,
+            chunk_min: chunker::MIN_SIZE,
+            chunk_avg: chunker::AVG_SIZE,
+            chunk_max: chunker::MAX_SIZE,
+            encryption_key: None,
+            store: None,
+            concurrency: CfKvFsAsync::default_concurrency(),
+            legacy_index: false,
         }
     }
 
@@ -97,23 +125,87 @@ impl CfKvFsBuilder {
         self
     }
 
+    pub fn chunk_min(mut self, chunk_min: usize) -> Self {
+        self.chunk_min = chunk_min;
+        self
+    }
+
+    pub fn chunk_avg(mut self, chunk_avg: usize) -> Self {
+        self.chunk_avg = chunk_avg;
+        self
+    }
+
+    pub fn chunk_max(mut self, chunk_max: usize) -> Self {
+        self.chunk_max = chunk_max;
+        self
+    }
+
+    pub fn encryption_key(mut self, key: [u8; 32]) -> Self {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    pub fn store(mut self, store: Box<dyn BlobStore + Send + Sync>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Reads blobs whose index was written before the switch to full-width
+    /// `ChunkHash` keys, i.e. packed little-endian `i64`s instead of 32-byte
+    /// digests. New blobs are always written with the wider format.
+    pub fn legacy_index(mut self, legacy_index: bool) -> Self {
+        self.legacy_index = legacy_index;
+        self
+    }
+
+    /// Blocking entry point: builds the same [`CfKvFsAsync`] [`build_async`](Self::build_async)
+    /// would, and wraps it in its own `tokio` runtime so `CfKvFs` stays a thin
+    /// `block_on` layer over a single, shared implementation.
     pub fn build(self) -> Option<CfKvFs> {
+        let runtime = Runtime::new().ok()?;
+        let inner = self.build_async()?;
+        Some(CfKvFs { inner, runtime })
+    }
+
+    /// Async counterpart of [`build`](Self::build): chunk uploads/downloads
+    /// run concurrently, bounded by [`concurrency`](Self::concurrency).
+    pub fn build_async(self) -> Option<CfKvFsAsync> {
+        use asynk::{AsyncBlobStore, BlockingStoreAdapter, HttpKvStoreAsync};
+
         CfKvFs::set_kv_cache(self.path, self.table);
-        CfKvFs::inner_new(
-            self.endpoint,
-            self.prefix,
-            self.header,
-            self.pem,
-            self.reducer,
-        )
+        let store: Box<dyn AsyncBlobStore + Send + Sync> = match self.store {
+            Some(store) => Box::new(BlockingStoreAdapter::new(Arc::from(store))),
+            None => Box::new(HttpKvStoreAsync::new(
+                self.endpoint,
+                self.prefix,
+                self.header,
+                self.pem,
+            )?),
+        };
+        Some(CfKvFsAsync {
+            store,
+            reducer: self.reducer,
+            chunk_min: self.chunk_min,
+            chunk_avg: self.chunk_avg,
+            chunk_max: self.chunk_max,
+            encryption_key: self.encryption_key,
+            concurrency: self.concurrency,
+            legacy_index: self.legacy_index,
+        })
     }
 }
 
+/// Blocking facade over [`CfKvFsAsync`]: every method just drives the async
+/// implementation to completion on an owned `tokio` runtime, so chunking,
+/// encryption, retry and legacy-read logic lives in exactly one place.
 pub struct CfKvFs {
-    client: Client,
-    endpoint: String,
-    prefix: String,
-    reducer: Option<Box<dyn Fn(Vec<u8>) -> Vec<u8> + Sync>>,
+    inner: CfKvFsAsync,
+    runtime: Runtime,
 }
 
 impl CfKvFs {
@@ -130,45 +222,12 @@ impl CfKvFs {
         E: Into<String>,
         P: Into<String>,
     {
-        Self::inner_new(endpoint, prefix, None, None, Some(Box::new(|data| data)))
-    }
-
-    fn inner_new<E, P>(
-        endpoint: E,
-        prefix: P,
-        header: Option<HeaderMap>,
-        pem: Option<Vec<u8>>,
-        reducer: Option<Box<dyn Fn(Vec<u8>) -> Vec<u8> + Sync>>,
-    ) -> Option<Self>
-    where
-        E: Into<String>,
-        P: Into<String>,
-    {
-        let mut builder = Client::builder()
-            .redirect(Policy::none())
-            .no_proxy()
-            .http2_prior_knowledge();
-        if let Some(header) = header {
-            builder = builder.default_headers(header);
-        }
-        if let Some(pem) = pem {
-            if let Ok(identity) = Identity::from_pem(&pem) {
-                builder = builder.identity(identity);
-            }
-        }
-        if let Ok(client) = builder.build() {
-            Some(Self {
-                client,
-                endpoint: endpoint.into(),
-                prefix: prefix.into(),
-                reducer,
-            })
-        } else {
-            None
-        }
+        Self::builder(endpoint, prefix)
+            .reducer(|data| data)
+            .build()
     }
 
-    fn set_kv_cache(
+    pub(crate) fn set_kv_cache(
         path: Option<PathBuf>,
         name: Option<String>,
     ) -> Arc<Mutex<Box<dyn KvCache + Send + Sync>>> {
@@ -189,111 +248,101 @@ impl CfKvFs {
         KV_CACHE.clone()
     }
 
-    fn get_kv_cache() -> Arc<Mutex<Box<dyn KvCache + Send + Sync>>> {
+    pub(crate) fn get_kv_cache() -> Arc<Mutex<Box<dyn KvCache + Send + Sync>>> {
         Self::set_kv_cache(None, None)
     }
 
-    fn post_data(&self, name: &str, data: Vec<u8>, index: bool) -> i64 {
-        let mut retry = 0;
-        let data = if let (Some(reducer), false) = (&self.reducer, index) {
-            reducer(data)
-        } else {
-            data
-        };
-        let hash = get_hash(&data);
-        while let Err(err) = self
-            .client
-            .post(format!(
-                "{}/{}/{}:{}",
-                self.endpoint,
-                self.prefix,
-                name,
-                if index {
-                    "index".into()
-                } else {
-                    hash.to_string()
-                }
-            ))
-            .body(data.clone())
-            .send()
-        {
-            if retry > 3 {
-                error!("Failed to save blob: {}", err);
-                return 0;
-            } else {
-                retry += 1;
-            }
-        }
-        return hash;
-    }
-
-    pub fn put_blob(&self, name: &str, data: Vec<u8>) {
-        let chunked_vec = data.chunks(CHUNK_SIZE).collect::<Vec<_>>();
-        let hash_list = chunked_vec
-            .par_iter()
-            .map(|chunk| self.post_data(name, chunk.to_vec(), false).to_le_bytes())
-            .flatten()
-            .collect::<Vec<_>>();
-        self.post_data(name, hash_list, true);
+    pub fn put_blob(&self, name: &str, data: Vec<u8>) -> Result<(), CfKvFsError> {
+        self.runtime.block_on(self.inner.put_blob(name, data))
     }
 
-    fn get_data(&self, name: &str, hash: i64) -> Result<Vec<u8>, CfKvFsError> {
-        let key = format!(
-            "{}:{}",
-            name,
-            if hash == 0 {
-                "index".into()
-            } else {
-                hash.to_string()
+    /// Streaming counterpart of [`put_blob`](Self::put_blob): pulls from
+    /// `reader` and chunks incrementally instead of buffering the whole blob,
+    /// so memory use stays bounded by a small multiple of `chunk_max`
+    /// regardless of the blob's total size. Errs instead of silently writing
+    /// a truncated index if any chunk failed to upload.
+    pub fn put_reader<R: Read>(&self, name: &str, mut reader: R) -> Result<(), CfKvFsError> {
+        let mut buf = Vec::new();
+        let mut read_buf = vec![0u8; self.inner.chunk_max];
+        let mut hash_list = Vec::new();
+        let mut failed = 0usize;
+        loop {
+            let n = reader.read(&mut read_buf)?;
+            if n == 0 {
+                break;
             }
-        );
-        if let Ok(Some(value)) = Self::get_kv_cache().lock().unwrap().get(key.clone()) {
-            return Ok(value);
-        }
-        let mut retry = 0;
-        let mut buf: Vec<u8> = vec![];
-        while let Err(err) = self
-            .client
-            .get(format!("{}/{}/{}", self.endpoint, self.prefix, key))
-            .send()
-            .and_then(|mut resp| resp.copy_to(&mut buf))
-            .map_err(CfKvFsError::ReqwestError)
-            .and_then(|_| {
-                if hash == 0 || get_hash(&buf) == hash {
-                    Ok(())
-                } else {
-                    Err(CfKvFsError::HashError)
+            buf.extend_from_slice(&read_buf[..n]);
+            while buf.len() > self.inner.chunk_max {
+                let len =
+                    FastCdcChunker::new(&buf, self.inner.chunk_min, self.inner.chunk_avg, self.inner.chunk_max)
+                        .next()
+                        .expect("non-empty buffer yields at least one chunk")
+                        .len();
+                let chunk = buf.drain(..len).collect::<Vec<_>>();
+                match self.runtime.block_on(self.inner.post_data(name, chunk, false)) {
+                    Some(hash) => hash_list.extend_from_slice(hash.as_bytes()),
+                    None => failed += 1,
                 }
-            })
-        {
-            if retry > 3 {
-                return Err(err);
-            } else {
-                retry += 1;
             }
         }
-        let data = Self::get_kv_cache().lock().unwrap().put(key, buf)?;
-        if let (Some(reducer), false) = (&self.reducer, hash == 0) {
-            Ok(reducer(data))
-        } else {
-            Ok(data)
+        while !buf.is_empty() {
+            let len = FastCdcChunker::new(&buf, self.inner.chunk_min, self.inner.chunk_avg, self.inner.chunk_max)
+                .next()
+                .expect("non-empty buffer yields at least one chunk")
+                .len();
+            let chunk = buf.drain(..len).collect::<Vec<_>>();
+            match self.runtime.block_on(self.inner.post_data(name, chunk, false)) {
+                Some(hash) => hash_list.extend_from_slice(hash.as_bytes()),
+                None => failed += 1,
+            }
+        }
+        if failed > 0 {
+            return Err(CfKvFsError::UploadFailed(failed));
         }
+        if let Err(err) = self
+            .runtime
+            .block_on(self.inner.append_log(&format!("{}:chunks", name), &hash_list))
+        {
+            error!("Failed to record chunk log: {}", err);
+        }
+        self.runtime
+            .block_on(self.inner.post_data(name, hash_list, true))
+            .ok_or(CfKvFsError::UploadFailed(1))?;
+        Ok(())
     }
 
     pub fn get_blob(&self, name: &str) -> Result<Vec<u8>, CfKvFsError> {
-        let data = self.get_data(name, 0)?;
-        let hashes = data
-            .chunks(8)
-            .map(|hash| hash.try_into())
-            .collect::<Result<Vec<[u8; 8]>, _>>()?;
-        Ok(hashes
-            .par_iter()
-            .map(|hash| self.get_data(name, i64::from_le_bytes(*hash)))
-            .collect::<Result<Vec<_>, CfKvFsError>>()?
-            .iter()
-            .flatten()
-            .cloned()
-            .collect())
+        self.runtime.block_on(self.inner.get_blob(name))
+    }
+
+    /// Streaming counterpart of [`get_blob`](Self::get_blob): fetches the
+    /// index, then writes each chunk to `writer` in order as it's verified,
+    /// rather than collecting the whole blob in memory first.
+    pub fn get_writer<W: Write>(&self, name: &str, mut writer: W) -> Result<(), CfKvFsError> {
+        if self.inner.legacy_index {
+            let data = self.runtime.block_on(self.inner.get_data_legacy(name, 0))?;
+            let hashes = data
+                .chunks(8)
+                .map(|hash| hash.try_into())
+                .collect::<Result<Vec<[u8; 8]>, _>>()?;
+            for hash in hashes {
+                let chunk = self
+                    .runtime
+                    .block_on(self.inner.get_data_legacy(name, i64::from_le_bytes(hash)))?;
+                writer.write_all(&chunk)?;
+            }
+        } else {
+            let data = self.runtime.block_on(self.inner.get_data(name, None))?;
+            if data.len() % 32 != 0 {
+                return Err(CfKvFsError::MalformedIndex);
+            }
+            for chunk in data.chunks(32) {
+                let hash = ChunkHash::try_from(chunk)?;
+                let chunk = self.runtime.block_on(self.inner.get_data(name, Some(hash)))?;
+                writer.write_all(&chunk)?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -305,7 +354,8 @@ fn test_upload() {
         .pem(pem.to_vec())
         .build()
         .unwrap();
-    cf.put_blob("test.bin", std::fs::read("test.bin").unwrap());
+    cf.put_blob("test.bin", std::fs::read("test.bin").unwrap())
+        .unwrap();
 }
 
 #[test]
@@ -318,3 +368,103 @@ fn test_download() {
     let bin = cf.get_blob("test.bin").unwrap();
     std::fs::write("test1.bin", bin).unwrap();
 }
+
+#[tokio::test]
+async fn test_mem_store_roundtrip_async() {
+    let cf = CfKvFs::builder("unused", "unused")
+        .store(Box::new(store::MemBlobStore::new()))
+        .concurrency(4)
+        .build_async()
+        .unwrap();
+    let data = b"hello async content-addressed world".to_vec();
+    cf.put_blob("mem-async.bin", data.clone()).await.unwrap();
+    assert_eq!(cf.get_blob("mem-async.bin").await.unwrap(), data);
+}
+
+#[test]
+fn test_mem_store_roundtrip() {
+    let cf = CfKvFs::builder("unused", "unused")
+        .store(Box::new(store::MemBlobStore::new()))
+        .build()
+        .unwrap();
+    let data = b"hello content-addressed world".to_vec();
+    cf.put_blob("mem.bin", data.clone()).unwrap();
+    assert_eq!(cf.get_blob("mem.bin").unwrap(), data);
+}
+
+#[test]
+fn test_snapshot_roundtrip_and_gc() {
+    let cf = CfKvFs::builder("unused", "unused")
+        .store(Box::new(store::MemBlobStore::new()))
+        .build()
+        .unwrap();
+    let v1 = b"version one".to_vec();
+    let v2 = b"version two, longer".to_vec();
+    let id1 = cf.put_snapshot("snap.bin", v1.clone()).unwrap();
+    let id2 = cf.put_snapshot("snap.bin", v2.clone()).unwrap();
+    assert_eq!(cf.get_snapshot("snap.bin", id1).unwrap(), v1);
+    assert_eq!(cf.get_snapshot("snap.bin", id2).unwrap(), v2);
+    assert_eq!(cf.get_blob("snap.bin").unwrap(), v2);
+    assert_eq!(cf.list_snapshots("snap.bin").unwrap().len(), 2);
+    assert!(cf.gc("snap.bin", false).unwrap().is_empty());
+}
+
+#[test]
+fn test_gc_collects_chunks_orphaned_by_put_blob() {
+    let cf = CfKvFs::builder("unused", "unused")
+        .store(Box::new(store::MemBlobStore::new()))
+        .build()
+        .unwrap();
+    let v1 = b"version one".to_vec();
+    let v2 = b"version two, longer".to_vec();
+    cf.put_blob("plain.bin", v1).unwrap();
+    // No snapshots taken, so overwriting the blob orphans v1's chunk: nothing
+    // but the stale :chunks log remembers it ever existed.
+    cf.put_blob("plain.bin", v2.clone()).unwrap();
+    let garbage = cf.gc("plain.bin", false).unwrap();
+    assert_eq!(garbage.len(), 1);
+    assert_eq!(cf.gc("plain.bin", true).unwrap(), garbage);
+    // gc is a maintenance operation and must be safe to run repeatedly: the
+    // :chunks log was compacted, so the already-collected hash isn't
+    // re-proposed and a second pass is a no-op rather than an error.
+    assert!(cf.gc("plain.bin", true).unwrap().is_empty());
+    assert_eq!(cf.get_blob("plain.bin").unwrap(), v2);
+}
+
+#[test]
+fn test_fs_store_snapshot_and_gc() {
+    let dir = std::env::temp_dir().join(format!("cfkvfs-fs-store-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let cf = CfKvFs::builder("unused", "unused")
+        .store(Box::new(store::FsBlobStore::new(&dir)))
+        .build()
+        .unwrap();
+    // No :snaps/:chunks log has ever been written for this name yet, so a
+    // missing file must read back as CfKvFsError::NotFound (and so as an
+    // empty result here), not bubble up the raw filesystem error.
+    assert!(cf.list_snapshots("fs.bin").unwrap().is_empty());
+    assert!(cf.gc("fs.bin", false).unwrap().is_empty());
+    let data = b"hello fs-backed content-addressed world".to_vec();
+    let id = cf.put_snapshot("fs.bin", data.clone()).unwrap();
+    assert_eq!(cf.get_snapshot("fs.bin", id).unwrap(), data);
+    assert_eq!(cf.list_snapshots("fs.bin").unwrap().len(), 1);
+    assert!(cf.gc("fs.bin", false).unwrap().is_empty());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_stream_roundtrip() {
+    let cf = CfKvFs::builder("unused", "unused")
+        .store(Box::new(store::MemBlobStore::new()))
+        .chunk_min(8)
+        .chunk_avg(16)
+        .chunk_max(32)
+        .build()
+        .unwrap();
+    let data = b"hello streamed content-addressed world, long enough to span chunks".to_vec();
+    cf.put_reader("stream.bin", std::io::Cursor::new(data.clone()))
+        .unwrap();
+    let mut out = Vec::new();
+    cf.get_writer("stream.bin", &mut out).unwrap();
+    assert_eq!(out, data);
+}
@@ -0,0 +1,221 @@
+use super::*;
+use std::{
+    collections::HashSet,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A snapshot's id is the content hash of its manifest, so identical versions
+/// (e.g. re-snapshotting unchanged data) collapse to the same id for free.
+/// With an encryption key set, every chunk is sealed under a random nonce, so
+/// re-snapshotting unchanged data yields new chunk hashes and a new id.
+pub type SnapshotId = ChunkHash;
+
+/// One entry in a name's `:snaps` log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotMeta {
+    pub id: SnapshotId,
+    pub timestamp: u64,
+}
+
+fn collect_hashes(index: &[u8], into: &mut HashSet<ChunkHash>) {
+    for chunk in index.chunks(32) {
+        if let Ok(hash) = ChunkHash::try_from(chunk) {
+            into.insert(hash);
+        }
+    }
+}
+
+impl CfKvFsAsync {
+    async fn put_manifest(&self, name: &str, manifest: Vec<u8>) -> Option<SnapshotId> {
+        let data = if let Some(key) = &self.encryption_key {
+            let nonce = crypto::derive_nonce(&manifest);
+            match crypto::seal(key, &nonce, &manifest) {
+                Ok(sealed) => sealed,
+                Err(err) => {
+                    error!("Failed to encrypt snapshot manifest: {}", err);
+                    return None;
+                }
+            }
+        } else {
+            manifest
+        };
+        let id = ChunkHash::compute(&data);
+        let key = format!("{}:snap:{}", name, id);
+        let mut retry = 0;
+        while let Err(err) = self.store.put(&key, data.clone()).await {
+            if retry > 3 {
+                error!("Failed to save snapshot manifest: {}", err);
+                return None;
+            } else {
+                retry += 1;
+            }
+        }
+        Some(id)
+    }
+
+    async fn get_manifest(&self, name: &str, id: SnapshotId) -> Result<Vec<u8>, CfKvFsError> {
+        let key = format!("{}:snap:{}", name, id);
+        let mut retry = 0;
+        let buf: Vec<u8>;
+        loop {
+            match self.store.get(&key).await.and_then(|data| {
+                if ChunkHash::compute(&data) == id {
+                    Ok(data)
+                } else {
+                    Err(CfKvFsError::HashError)
+                }
+            }) {
+                Ok(data) => {
+                    buf = data;
+                    break;
+                }
+                Err(err) => {
+                    if retry > 3 {
+                        return Err(err);
+                    } else {
+                        retry += 1;
+                    }
+                }
+            }
+        }
+        if let Some(key) = &self.encryption_key {
+            crypto::open(key, &buf)
+        } else {
+            Ok(buf)
+        }
+    }
+
+    /// Uploads `data` as a new immutable version of `name`: chunks are shared
+    /// with every other version of `name` by content address, only the
+    /// manifest (the list of chunk hashes) is version-specific. With an
+    /// encryption key set, chunks are sealed under random nonces and so are
+    /// not shared between versions. Also refreshes
+    /// `:index` so `get_blob` keeps returning the latest version.
+    pub async fn put_snapshot(&self, name: &str, data: Vec<u8>) -> Result<SnapshotId, CfKvFsError> {
+        let hash_list = self.build_hash_list(name, data).await?;
+        self.post_data(name, hash_list.clone(), true)
+            .await
+            .ok_or(CfKvFsError::UploadFailed(1))?;
+        let id = self
+            .put_manifest(name, hash_list)
+            .await
+            .ok_or(CfKvFsError::UploadFailed(1))?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut entry = id.as_bytes().to_vec();
+        entry.extend_from_slice(&timestamp.to_le_bytes());
+        if let Err(err) = self.append_log(&format!("{}:snaps", name), &entry).await {
+            error!("Failed to record snapshot log: {}", err);
+        }
+        Ok(id)
+    }
+
+    /// Lists every version of `name` recorded in its `:snaps` log, oldest first.
+    pub async fn list_snapshots(&self, name: &str) -> Result<Vec<SnapshotMeta>, CfKvFsError> {
+        let log = match self.store.get(&format!("{}:snaps", name)).await {
+            Ok(data) => data,
+            Err(CfKvFsError::NotFound) => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        if log.len() % 40 != 0 {
+            return Err(CfKvFsError::MalformedIndex);
+        }
+        log.chunks(40)
+            .map(|entry| {
+                Ok(SnapshotMeta {
+                    id: ChunkHash::try_from(&entry[..32])?,
+                    timestamp: u64::from_le_bytes(entry[32..40].try_into()?),
+                })
+            })
+            .collect()
+    }
+
+    /// Reads one specific version of `name` by [`SnapshotId`].
+    pub async fn get_snapshot(&self, name: &str, id: SnapshotId) -> Result<Vec<u8>, CfKvFsError> {
+        let manifest = self.get_manifest(name, id).await?;
+        self.assemble_blob(name, manifest).await
+    }
+
+    /// Walks every live manifest of `name` (its current `:index` plus every
+    /// snapshot in `:snaps`) and diffs the referenced chunk hashes against the
+    /// `:chunks` log of everything ever uploaded, returning the chunks that
+    /// are no longer referenced by any version. Pass `delete` to also remove
+    /// them from the store and compact the `:chunks` log down to what's still
+    /// live, so a later `gc` call doesn't re-propose the same hashes and
+    /// isn't tripped up by them already being gone; otherwise this only
+    /// reports what's garbage and leaves the log untouched.
+    pub async fn gc(&self, name: &str, delete: bool) -> Result<Vec<ChunkHash>, CfKvFsError> {
+        let mut live = HashSet::new();
+        // Read `:index` from the store rather than through `get_data`, whose
+        // cache can still hold an index that a later upload replaced.
+        match self.store.get(&format!("{}:index", name)).await {
+            Ok(index) => {
+                let index = if let Some(key) = &self.encryption_key {
+                    crypto::open(key, &index)?
+                } else {
+                    index
+                };
+                collect_hashes(&index, &mut live)
+            }
+            Err(CfKvFsError::NotFound) => {}
+            Err(err) => return Err(err),
+        }
+        for meta in self.list_snapshots(name).await? {
+            let manifest = self.get_manifest(name, meta.id).await?;
+            collect_hashes(&manifest, &mut live);
+        }
+        let candidates = match self.store.get(&format!("{}:chunks", name)).await {
+            Ok(data) => data,
+            Err(CfKvFsError::NotFound) => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        let mut seen = HashSet::new();
+        let mut garbage = Vec::new();
+        let mut remaining = Vec::new();
+        for chunk in candidates.chunks(32) {
+            if let Ok(hash) = ChunkHash::try_from(chunk) {
+                if seen.insert(hash) {
+                    if live.contains(&hash) {
+                        remaining.extend_from_slice(hash.as_bytes());
+                    } else {
+                        garbage.push(hash);
+                    }
+                }
+            }
+        }
+        if delete {
+            for hash in &garbage {
+                match self.store.delete(&format!("{}:{}", name, hash)).await {
+                    Ok(()) | Err(CfKvFsError::NotFound) => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            self.store.put(&format!("{}:chunks", name), remaining).await?;
+        }
+        Ok(garbage)
+    }
+}
+
+impl CfKvFs {
+    /// Blocking wrapper around [`CfKvFsAsync::put_snapshot`].
+    pub fn put_snapshot(&self, name: &str, data: Vec<u8>) -> Result<SnapshotId, CfKvFsError> {
+        self.runtime.block_on(self.inner.put_snapshot(name, data))
+    }
+
+    /// Blocking wrapper around [`CfKvFsAsync::list_snapshots`].
+    pub fn list_snapshots(&self, name: &str) -> Result<Vec<SnapshotMeta>, CfKvFsError> {
+        self.runtime.block_on(self.inner.list_snapshots(name))
+    }
+
+    /// Blocking wrapper around [`CfKvFsAsync::get_snapshot`].
+    pub fn get_snapshot(&self, name: &str, id: SnapshotId) -> Result<Vec<u8>, CfKvFsError> {
+        self.runtime.block_on(self.inner.get_snapshot(name, id))
+    }
+
+    /// Blocking wrapper around [`CfKvFsAsync::gc`].
+    pub fn gc(&self, name: &str, delete: bool) -> Result<Vec<ChunkHash>, CfKvFsError> {
+        self.runtime.block_on(self.inner.gc(name, delete))
+    }
+}
@@ -0,0 +1,163 @@
+use super::*;
+use reqwest::{blocking::Client, header::HeaderMap, redirect::Policy, Identity, StatusCode};
+use std::{collections::HashMap, fs, io::ErrorKind};
+
+/// Backend abstraction for where blob chunks and index data actually live.
+/// `CfKvFs` keeps the retry and hash-verification logic so every backend gets
+/// it for free; implementations only need to move bytes in and out by key.
+pub trait BlobStore {
+    fn put(&self, key: &str, data: Vec<u8>) -> Result<(), CfKvFsError>;
+    fn get(&self, key: &str) -> Result<Vec<u8>, CfKvFsError>;
+    fn delete(&self, key: &str) -> Result<(), CfKvFsError>;
+}
+
+/// Default backend: a Cloudflare-KV-style REST endpoint, one object per key.
+pub struct HttpKvStore {
+    client: Client,
+    endpoint: String,
+    prefix: String,
+}
+
+impl HttpKvStore {
+    pub fn new(
+        endpoint: String,
+        prefix: String,
+        header: Option<HeaderMap>,
+        pem: Option<Vec<u8>>,
+    ) -> Option<Self> {
+        let mut builder = Client::builder()
+            .redirect(Policy::none())
+            .no_proxy()
+            .http2_prior_knowledge();
+        if let Some(header) = header {
+            builder = builder.default_headers(header);
+        }
+        if let Some(pem) = pem {
+            if let Ok(identity) = Identity::from_pem(&pem) {
+                builder = builder.identity(identity);
+            }
+        }
+        builder
+            .build()
+            .ok()
+            .map(|client| Self { client, endpoint, prefix })
+    }
+}
+
+impl BlobStore for HttpKvStore {
+    fn put(&self, key: &str, data: Vec<u8>) -> Result<(), CfKvFsError> {
+        self.client
+            .post(format!("{}/{}/{}", self.endpoint, self.prefix, key))
+            .body(data)
+            .send()
+            .map(|_| ())
+            .map_err(CfKvFsError::ReqwestError)
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, CfKvFsError> {
+        let mut resp = self
+            .client
+            .get(format!("{}/{}/{}", self.endpoint, self.prefix, key))
+            .send()
+            .map_err(CfKvFsError::ReqwestError)?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Err(CfKvFsError::NotFound);
+        }
+        let mut buf = vec![];
+        resp.copy_to(&mut buf).map_err(CfKvFsError::ReqwestError)?;
+        Ok(buf)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), CfKvFsError> {
+        let resp = self
+            .client
+            .delete(format!("{}/{}/{}", self.endpoint, self.prefix, key))
+            .send()
+            .map_err(CfKvFsError::ReqwestError)?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Err(CfKvFsError::NotFound);
+        }
+        Ok(())
+    }
+}
+
+/// Maps keys to files under a root directory; handy for exercising the
+/// chunk/index format locally without a live endpoint.
+pub struct FsBlobStore {
+    root: PathBuf,
+}
+
+impl FsBlobStore {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    fn path_for(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+impl BlobStore for FsBlobStore {
+    fn put(&self, key: &str, data: Vec<u8>) -> Result<(), CfKvFsError> {
+        let path = self.path_for(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, CfKvFsError> {
+        fs::read(self.path_for(key)).map_err(|err| match err.kind() {
+            ErrorKind::NotFound => CfKvFsError::NotFound,
+            _ => err.into(),
+        })
+    }
+
+    fn delete(&self, key: &str) -> Result<(), CfKvFsError> {
+        fs::remove_file(self.path_for(key)).map_err(|err| match err.kind() {
+            ErrorKind::NotFound => CfKvFsError::NotFound,
+            _ => err.into(),
+        })
+    }
+}
+
+/// In-memory backend so `put_blob`/`get_blob` can be exercised in tests
+/// without a live server.
+#[derive(Default)]
+pub struct MemBlobStore {
+    data: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl MemBlobStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl BlobStore for MemBlobStore {
+    fn put(&self, key: &str, data: Vec<u8>) -> Result<(), CfKvFsError> {
+        self.data.lock().unwrap().insert(key.to_string(), data);
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, CfKvFsError> {
+        self.data
+            .lock()
+            .unwrap()
+            .get(key)
+            .cloned()
+            .ok_or(CfKvFsError::NotFound)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), CfKvFsError> {
+        self.data
+            .lock()
+            .unwrap()
+            .remove(key)
+            .map(|_| ())
+            .ok_or(CfKvFsError::NotFound)
+    }
+}
@@ -4,11 +4,71 @@ use sha3::{
     Shake256,
 };
 use std::{
-    convert::TryInto,
+    convert::{TryFrom, TryInto},
+    fmt,
     io::Read,
+    str::FromStr,
     sync::{Arc, Mutex},
 };
 
+/// Full-width content address: a 32-byte Shake256 digest, hex-encoded for use
+/// as a KV key. Wide enough that a store holding hundreds of millions of
+/// chunks has no meaningful birthday-collision risk, unlike the truncated
+/// 64-bit `get_hash` below.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkHash([u8; 32]);
+
+impl ChunkHash {
+    pub fn compute<D: AsRef<[u8]>>(data: D) -> Self {
+        let mut hasher = Shake256::default();
+        hasher.update(data);
+        let mut buf = [0u8; 32];
+        if hasher.finalize_xof_dirty().read(&mut buf).is_err() {
+            buf = [0u8; 32];
+        }
+        Self(buf)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for ChunkHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ChunkHash {
+    type Err = CfKvFsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 64 {
+            return Err(CfKvFsError::MalformedIndex);
+        }
+        let mut buf = [0u8; 32];
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+                .map_err(|_| CfKvFsError::MalformedIndex)?;
+        }
+        Ok(Self(buf))
+    }
+}
+
+impl TryFrom<&[u8]> for ChunkHash {
+    type Error = CfKvFsError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        Ok(Self(
+            bytes.try_into().map_err(|_| CfKvFsError::MalformedIndex)?,
+        ))
+    }
+}
+
 pub fn get_hash<D: AsRef<[u8]>>(data: D) -> i64 {
     lazy_static! {
         static ref HASHER: Arc<Mutex<Shake256>> = Arc::new(Mutex::new(Shake256::default()));